@@ -1,4 +1,25 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The layout version of the actor's state object. Bump this whenever `State` changes
+/// shape, and add the upgrade to `src/migrations.rs`; it is embedded in the deployment
+/// manifest and exposed by `get_version`.
+const STATE_VERSION: u64 = 3;
+
+/// Name of the custom wasm section holding the deployment manifest.
+const MANIFEST_SECTION: &str = "fvm_manifest";
+
 fn main() {
+    write_manifest();
+
+    // Panic locations embed source paths in the wasm. Remap the checkout and the cargo
+    // home (registry and git dependency sources) so the code CID doesn't depend on where
+    // the build ran.
+    let source_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let cargo_home = cargo_home();
+
     use wasm_builder::WasmBuilder;
     WasmBuilder::new()
         .with_current_project()
@@ -8,5 +29,144 @@ fn main() {
         .append_to_rust_flags("-Coverflow-checks=true")
         .append_to_rust_flags("-Clto=true")
         .append_to_rust_flags("-Copt-level=z")
+        .append_to_rust_flags(format!("--remap-path-prefix={}=/build", source_dir))
+        .append_to_rust_flags(format!(
+            "--remap-path-prefix={}=/cargo",
+            cargo_home.display()
+        ))
         .build()
 }
+
+/// Returns `$CARGO_HOME`, falling back to cargo's default of `~/.cargo`.
+fn cargo_home() -> PathBuf {
+    match env::var_os("CARGO_HOME") {
+        Some(home) => home.into(),
+        None => PathBuf::from(env::var_os("HOME").unwrap_or_default()).join(".cargo"),
+    }
+}
+
+/// Generates `$OUT_DIR/manifest.rs`, which is included by the `manifest` module.
+///
+/// The same information is emitted twice: as constants for `get_version`, and as a JSON
+/// document in a custom wasm section, so operators can match a code CID back to the exact
+/// source build without instantiating the actor.
+fn write_manifest() {
+    watch_git_state();
+
+    let crate_version = env::var("CARGO_PKG_VERSION").unwrap();
+    let git_commit = git_commit().unwrap_or_else(|| "unknown".to_owned());
+
+    // Cargo exposes enabled features to build scripts as CARGO_FEATURE_<NAME>.
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(k, _)| {
+            k.strip_prefix("CARGO_FEATURE_")
+                .map(|f| f.to_lowercase().replace('_', "-"))
+        })
+        .collect();
+    features.sort();
+
+    let json = format!(
+        "{{\"crate_version\":{:?},\"git_commit\":{:?},\"features\":{:?},\"state_version\":{}}}",
+        crate_version, git_commit, features, STATE_VERSION
+    );
+
+    let out = format!(
+        r#"/// The crate version the actor was built from.
+pub const CRATE_VERSION: &str = {crate_version:?};
+/// The git commit the actor was built from, suffixed with `-dirty` if the tree had
+/// uncommitted changes, or `unknown` outside a git checkout.
+pub const GIT_COMMIT: &str = {git_commit:?};
+/// The cargo features enabled for this build.
+pub const FEATURES: &[&str] = &{features:?};
+/// The layout version of the actor's state object.
+pub const STATE_VERSION: u64 = {STATE_VERSION};
+
+/// The manifest as JSON, placed in the `{MANIFEST_SECTION}` custom section of the wasm.
+#[used]
+#[cfg_attr(target_arch = "wasm32", link_section = "{MANIFEST_SECTION}")]
+static MANIFEST_SECTION: [u8; {len}] = {json_bytes:?};
+"#,
+        len = json.len(),
+        json_bytes = json.as_bytes(),
+    );
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("manifest.rs");
+    fs::write(dest, out).unwrap();
+}
+
+/// Reruns the build script when the commit or the state of the tree changes.
+///
+/// wasm-builder emits its own `rerun-if-changed` lines, which turns off cargo's default of
+/// rerunning on any change in the package, so without these `GIT_COMMIT` goes stale.
+fn watch_git_state() {
+    let dir = match env::var("CARGO_MANIFEST_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => return,
+    };
+    let git = |args: &[&str]| -> Option<String> {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(&dir)
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        Some(String::from_utf8(output.stdout).ok()?.trim().to_owned())
+    };
+    // Both may be relative to the manifest directory, and differ inside a worktree.
+    let (git_dir, common_dir) = match (
+        git(&["rev-parse", "--git-dir"]),
+        git(&["rev-parse", "--git-common-dir"]),
+    ) {
+        (Some(git_dir), Some(common_dir)) => (dir.join(git_dir), dir.join(common_dir)),
+        _ => return,
+    };
+
+    // The manifest only records the commit, so tags are not watched; packed-refs can
+    // hold the current branch.
+    let mut watched = vec![
+        git_dir.join("HEAD"),
+        git_dir.join("index"),
+        common_dir.join("packed-refs"),
+    ];
+    if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+        watched.push(common_dir.join(head_ref));
+    }
+    // A missing path would make cargo rerun the script on every build.
+    for path in watched.iter().filter(|p| p.exists()) {
+        println!("cargo:rerun-if-changed={}", path.display());
+    }
+}
+
+/// Returns the full hash of `HEAD`, suffixed with `-dirty` if tracked files have
+/// uncommitted changes, if the build happens inside a git checkout.
+///
+/// Tags are deliberately left out: the manifest is part of the hashed wasm, so it
+/// must only depend on the commit being built, not on refs added after deployment.
+fn git_commit() -> Option<String> {
+    let dir = env::var("CARGO_MANIFEST_DIR").ok()?;
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(&dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let commit = String::from_utf8(output.stdout).ok()?.trim().to_owned();
+
+    let status = Command::new("git")
+        .args(["status", "--porcelain", "--untracked-files=no"])
+        .current_dir(&dir)
+        .output()
+        .ok()?;
+    if !status.status.success() {
+        return None;
+    }
+    Some(if status.stdout.is_empty() {
+        commit
+    } else {
+        format!("{}-dirty", commit)
+    })
+}
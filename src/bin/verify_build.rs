@@ -0,0 +1,309 @@
+//! Reproducible-build verification helper.
+//!
+//! Checks out the given commit into a fresh git worktree, rebuilds the actor
+//! wasm there with the settings pinned in `rust-toolchain`, `Cargo.lock` and
+//! `build.rs`, computes its code CID the same way the FVM does for actor
+//! bytecode (a raw-codec CID over the blake2b-256 hash of the wasm), and
+//! compares it against an on-chain code CID.
+//!
+//! Usage:
+//!
+//! ```text
+//! verify_build --rpc http://127.0.0.1:1234/rpc/v0 --actor f01234 [--commit <rev>] [--repo <path>]
+//! verify_build --code-cid bafk2bz... [--commit <rev>] [--repo <path>]
+//! ```
+//!
+//! `--repo` names a checkout of this repository and defaults to the one
+//! containing the current directory. `--commit` defaults to `HEAD`.
+//! Uncommitted changes in the checkout never affect the result. Only plain `http://` endpoints are supported for
+//! `--rpc`.
+
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::{Path, PathBuf};
+use std::process::{exit, Command};
+
+use anyhow::{anyhow, bail, Context, Result};
+use cid::multihash::{Code, MultihashDigest};
+use cid::Cid;
+use fvm_shared::IPLD_RAW;
+
+const CRATE_NAME: &str = "fil_hello_world_actor";
+
+struct Args {
+    rpc: Option<String>,
+    actor: Option<String>,
+    code_cid: Option<String>,
+    commit: String,
+    repo: PathBuf,
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(err) => {
+            eprintln!("error: {}", err);
+            eprintln!(
+                "usage: verify_build (--rpc <url> --actor <address> | --code-cid <cid>) [--commit <rev>] [--repo <path>]"
+            );
+            exit(2);
+        }
+    };
+
+    match run(args) {
+        Ok(true) => {}
+        Ok(false) => exit(1),
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+            exit(2);
+        }
+    }
+}
+
+fn parse_args() -> Result<Args> {
+    let mut args = Args {
+        rpc: None,
+        actor: None,
+        code_cid: None,
+        commit: "HEAD".to_owned(),
+        repo: PathBuf::from("."),
+    };
+    let mut it = std::env::args().skip(1);
+    while let Some(flag) = it.next() {
+        let mut value = || {
+            it.next()
+                .ok_or_else(|| anyhow!("missing value for {}", flag))
+        };
+        match flag.as_str() {
+            "--rpc" => args.rpc = Some(value()?),
+            "--actor" => args.actor = Some(value()?),
+            "--code-cid" => args.code_cid = Some(value()?),
+            "--commit" => args.commit = value()?,
+            "--repo" => args.repo = value()?.into(),
+            other => bail!("unknown argument {}", other),
+        }
+    }
+    match (&args.code_cid, &args.rpc, &args.actor) {
+        (Some(_), None, None) | (None, Some(_), Some(_)) => Ok(args),
+        _ => bail!("pass either --code-cid, or both --rpc and --actor"),
+    }
+}
+
+fn run(args: Args) -> Result<bool> {
+    let root = PathBuf::from(
+        git(&args.repo, &["rev-parse", "--show-toplevel"])
+            .with_context(|| format!("{} is not in a git checkout", args.repo.display()))?,
+    );
+    let commit = resolve_commit(&root, &args.commit)?;
+    let worktree = Worktree::add(&root, &commit)?;
+    let wasm_path = rebuild(&worktree.path)?;
+    let wasm = std::fs::read(&wasm_path)
+        .with_context(|| format!("failed to read {}", wasm_path.display()))?;
+
+    // The manifest is part of the hashed bytes, so a build that doesn't name
+    // exactly this commit can't match a deployment of it.
+    let built_from = embedded_commit(&wasm)?;
+    if built_from != commit {
+        bail!(
+            "rebuilt wasm names commit {:?} instead of {}; the build cannot be reproduced",
+            built_from,
+            commit
+        );
+    }
+    let local = code_cid(&wasm);
+
+    let onchain = match (args.code_cid, args.rpc, args.actor) {
+        (Some(cid), _, _) => parse_cid(&cid)?,
+        (None, Some(rpc), Some(actor)) => fetch_code_cid(&rpc, &actor)?,
+        _ => unreachable!("checked by parse_args"),
+    };
+
+    let verified = local == onchain;
+    println!("commit:         {}", commit);
+    println!("wasm size:      {} bytes", wasm.len());
+    println!("local code CID: {}", local);
+    println!("chain code CID: {}", onchain);
+    println!(
+        "result:         {}",
+        if verified { "VERIFIED" } else { "MISMATCH" }
+    );
+    Ok(verified)
+}
+
+/// Resolves `rev` to a full commit hash, and checks that the commit pins its
+/// dependency graph.
+fn resolve_commit(root: &Path, rev: &str) -> Result<String> {
+    let commit = git(
+        root,
+        &["rev-parse", "--verify", &format!("{}^{{commit}}", rev)],
+    )?;
+    if git(root, &["cat-file", "-e", &format!("{}:Cargo.lock", commit)]).is_err() {
+        bail!(
+            "commit {} does not track Cargo.lock; the build cannot be reproduced",
+            commit
+        );
+    }
+    Ok(commit)
+}
+
+/// A temporary, clean checkout of a single commit, removed on drop.
+struct Worktree {
+    repo: PathBuf,
+    path: PathBuf,
+}
+
+impl Worktree {
+    fn add(repo: &Path, commit: &str) -> Result<Self> {
+        let path = std::env::temp_dir().join(format!("verify_build-{}", std::process::id()));
+        let path_str = path.to_str().ok_or_else(|| anyhow!("non-UTF-8 temp dir"))?;
+        git(repo, &["worktree", "add", "--detach", path_str, commit])?;
+        Ok(Worktree {
+            repo: repo.to_owned(),
+            path,
+        })
+    }
+}
+
+impl Drop for Worktree {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.to_str() {
+            let _ = git(&self.repo, &["worktree", "remove", "--force", path]);
+        }
+    }
+}
+
+/// Runs a git command in `dir` and returns its trimmed stdout.
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8(output.stdout)?.trim().to_owned())
+}
+
+/// Rebuilds the release wasm in `dir` and returns its path.
+fn rebuild(dir: &Path) -> Result<PathBuf> {
+    // rust-toolchain pins the compiler; --locked makes the build fail rather
+    // than resolve a different dependency graph than the committed Cargo.lock.
+    let status = Command::new("cargo")
+        .args(["build", "--release", "--locked", "--lib"])
+        .current_dir(dir)
+        .status()
+        .context("failed to run cargo")?;
+    if !status.success() {
+        bail!("cargo build failed with {}", status);
+    }
+    Ok(target_dir(dir)?
+        .join("release/wbuild")
+        .join(CRATE_NAME)
+        .join(format!("{}.compact.wasm", CRATE_NAME)))
+}
+
+/// Asks cargo for the target directory, which honours `CARGO_TARGET_DIR` and
+/// `build.target-dir` in cargo config.
+fn target_dir(dir: &Path) -> Result<PathBuf> {
+    let output = Command::new("cargo")
+        .args(["metadata", "--format-version", "1", "--no-deps"])
+        .current_dir(dir)
+        .output()
+        .context("failed to run cargo metadata")?;
+    if !output.status.success() {
+        bail!("cargo metadata failed with {}", output.status);
+    }
+    let metadata = String::from_utf8(output.stdout)?;
+    let value = json_string_field(&metadata, "target_directory")
+        .ok_or_else(|| anyhow!("no target_directory in cargo metadata"))?;
+    Ok(value.into())
+}
+
+/// Returns the commit recorded in the wasm's `fvm_manifest` section.
+fn embedded_commit(wasm: &[u8]) -> Result<String> {
+    // The manifest JSON is stored verbatim, so a byte search is enough.
+    let needle = br#""git_commit":""#;
+    let start = wasm
+        .windows(needle.len())
+        .position(|w| w == needle)
+        .ok_or_else(|| anyhow!("no manifest found in the rebuilt wasm"))?;
+    let end = wasm.len().min(start + 256);
+    json_string_field(&String::from_utf8_lossy(&wasm[start..end]), "git_commit")
+        .ok_or_else(|| anyhow!("malformed manifest in the rebuilt wasm"))
+}
+
+/// Picks the string value of `"<field>":"..."` out of a JSON document,
+/// unescaping `\\` and `\"`. Good enough for the fixed-shape documents read here.
+fn json_string_field(json: &str, field: &str) -> Option<String> {
+    let key = format!("\"{}\":\"", field);
+    let start = json.find(&key)? + key.len();
+    let mut value = String::new();
+    let mut chars = json[start..].chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => value.push(chars.next()?),
+            c => value.push(c),
+        }
+    }
+}
+
+/// Computes the code CID of actor bytecode.
+fn code_cid(wasm: &[u8]) -> Cid {
+    Cid::new_v1(IPLD_RAW, Code::Blake2b256.digest(wasm))
+}
+
+fn parse_cid(s: &str) -> Result<Cid> {
+    Cid::try_from(s).map_err(|e| anyhow!("invalid CID {:?}: {}", s, e))
+}
+
+/// Fetches an actor's code CID through the Lotus `Filecoin.StateGetActor` JSON-RPC method.
+fn fetch_code_cid(rpc: &str, actor: &str) -> Result<Cid> {
+    let rest = rpc
+        .strip_prefix("http://")
+        .ok_or_else(|| anyhow!("only http:// RPC endpoints are supported"))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    };
+    let addr = if host.contains(':') {
+        host.to_owned()
+    } else {
+        format!("{}:80", host)
+    };
+
+    let body = format!(
+        r#"{{"jsonrpc":"2.0","method":"Filecoin.StateGetActor","params":[{:?},null],"id":1}}"#,
+        actor
+    );
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        path,
+        host,
+        body.len(),
+        body
+    );
+
+    let mut stream =
+        TcpStream::connect(&addr).with_context(|| format!("failed to connect to {}", addr))?;
+    stream.write_all(request.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    // The response is small and fixed in shape, so pick the code CID out of
+    // `"Code":{"/":"<cid>"}` rather than pulling in a JSON parser.
+    let start = response
+        .find(r#""Code":{"/":""#)
+        .map(|i| i + r#""Code":{"/":""#.len())
+        .ok_or_else(|| anyhow!("no code CID in RPC response: {}", response))?;
+    let end = response[start..]
+        .find('"')
+        .ok_or_else(|| anyhow!("malformed RPC response: {}", response))?;
+    parse_cid(&response[start..start + end])
+}
@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::convert::TryFrom;
 
 use anyhow::{anyhow, Result};
@@ -6,7 +7,67 @@ use cid::Cid;
 use fvm_ipld_blockstore::Block;
 use fvm_sdk as sdk;
 
+use crate::EXIT_WRITE_BUDGET_EXCEEDED;
+
+/// The maximum number of blocks a single invocation may write.
+pub const MAX_PUTS: u64 = 256;
+
+/// The maximum number of bytes a single invocation may write.
+pub const MAX_PUT_BYTES: u64 = 1 << 20;
+
+/// The blocks and bytes written so far by an invocation.
+#[derive(Default, Clone, Copy)]
+struct WriteTotals {
+    puts: u64,
+    bytes: u64,
+}
+
+impl WriteTotals {
+    /// Accounts for writing a block of `len` bytes, failing if that takes the
+    /// totals over the write budget.
+    fn charge(&mut self, len: u64) -> Result<()> {
+        self.puts += 1;
+        self.bytes += len;
+        if self.puts > MAX_PUTS || self.bytes > MAX_PUT_BYTES {
+            return Err(anyhow!(
+                "write budget exceeded: {} blocks / {} bytes (max {} / {})",
+                self.puts,
+                self.bytes,
+                MAX_PUTS,
+                MAX_PUT_BYTES
+            ));
+        }
+        Ok(())
+    }
+}
+
+thread_local! {
+    // Per invocation; see `runtime::CALLER_VALIDATED`.
+    static WRITES: Cell<WriteTotals> = Cell::new(WriteTotals::default());
+}
+
+/// Accounts for a block write, aborting the invocation with
+/// `EXIT_WRITE_BUDGET_EXCEEDED` if it exceeds the write budget. This bounds
+/// the state diff a single message can produce, whatever its inputs.
+fn charge_write(len: usize) {
+    let charged = WRITES.with(|writes| {
+        let mut totals = writes.get();
+        let charged = totals.charge(len as u64);
+        writes.set(totals);
+        charged
+    });
+    if let Err(err) = charged {
+        sdk::vm::abort(
+            EXIT_WRITE_BUDGET_EXCEEDED.value(),
+            Some(err.to_string().as_str()),
+        )
+    }
+}
+
 /// A blockstore that delegates to IPLD syscalls.
+///
+/// All writes count against a per-invocation budget; see `MAX_PUTS` and
+/// `MAX_PUT_BYTES`.
 pub struct Blockstore;
 
 impl fvm_ipld_blockstore::Blockstore for Blockstore {
@@ -30,6 +91,8 @@ impl fvm_ipld_blockstore::Blockstore for Blockstore {
     where
         D: AsRef<[u8]>,
     {
+        charge_write(block.data.as_ref().len());
+
         // TODO: Don't hard-code the size. Unfortunately, there's no good way to get it from the
         //  codec at the moment.
         const SIZE: u32 = 32;
@@ -38,3 +101,31 @@ impl fvm_ipld_blockstore::Blockstore for Blockstore {
         Ok(k)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allows_exactly_max_puts() {
+        let mut totals = WriteTotals::default();
+        for _ in 0..MAX_PUTS {
+            totals.charge(0).unwrap();
+        }
+        assert!(totals.charge(0).is_err());
+    }
+
+    #[test]
+    fn allows_exactly_max_put_bytes() {
+        let mut totals = WriteTotals::default();
+        totals.charge(MAX_PUT_BYTES - 1).unwrap();
+        totals.charge(1).unwrap();
+        assert!(totals.charge(1).is_err());
+    }
+
+    #[test]
+    fn rejects_a_single_oversized_block() {
+        assert!(WriteTotals::default().charge(MAX_PUT_BYTES).is_ok());
+        assert!(WriteTotals::default().charge(MAX_PUT_BYTES + 1).is_err());
+    }
+}
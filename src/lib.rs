@@ -1,82 +1,251 @@
 mod blockstore;
+mod manifest;
+mod migrations;
+pub mod params;
+pub mod runtime;
+#[cfg(feature = "testing")]
+mod testing;
 
-use crate::blockstore::Blockstore;
-use cid::multihash::Code;
+use crate::manifest::Manifest;
+use crate::runtime::{
+    validate_immediate_caller_accept_any, validate_immediate_caller_is, INIT_ACTOR_ADDR,
+};
+use anyhow::Result;
 use cid::Cid;
 use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
-use fvm_ipld_encoding::{to_vec, CborStore, RawBytes, DAG_CBOR};
+use fvm_ipld_encoding::{RawBytes, DAG_CBOR};
 use fvm_sdk as sdk;
 use fvm_sdk::NO_DATA_BLOCK_ID;
-use fvm_shared::ActorID;
-
-/// A macro to abort concisely.
-/// This should be part of the SDK as it's very handy.
-macro_rules! abort {
-    ($code:ident, $msg:literal $(, $ex:expr)*) => {
-        fvm_sdk::vm::abort(
-            fvm_shared::error::ExitCode::$code.value(),
-            Some(format!($msg, $($ex,)*).as_str()),
-        )
-    };
+use fvm_shared::error::ExitCode;
+use fvm_shared::MethodNum;
+pub use state_object::{abort, StateConfig, StateObject};
+
+pub use crate::blockstore::{MAX_PUTS, MAX_PUT_BYTES};
+
+/// Exit code for invocations that write more than `MAX_PUTS` blocks or
+/// `MAX_PUT_BYTES` bytes. This is the first actor-specific exit code.
+pub const EXIT_WRITE_BUDGET_EXCEEDED: ExitCode = ExitCode::new(32);
+
+/// An actor-specific exit code.
+pub struct ActorExitCode {
+    pub code: ExitCode,
+    /// The name of the constant defining the code.
+    pub name: &'static str,
+    /// Whether resending the same message may succeed. Clients can treat
+    /// other failures as final.
+    pub retryable: bool,
 }
 
+/// Every exit code the actor aborts with besides the standard `USR_*` ones.
+/// Listed by `get_interface`; add new codes here.
+pub const EXIT_CODES: &[ActorExitCode] = &[ActorExitCode {
+    code: EXIT_WRITE_BUDGET_EXCEEDED,
+    name: "EXIT_WRITE_BUDGET_EXCEEDED",
+    retryable: false,
+}];
+
 /// The state object.
-#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug, Default)]
+///
+/// Objects written by older versions of the actor are upgraded on read; see
+/// the `migrations` module.
+#[derive(Serialize_tuple, Deserialize_tuple, StateObject, Clone, Debug)]
 pub struct State {
+    /// The layout version of this object.
+    pub version: u64,
     pub count: u64,
+    /// Whether `invoke` rejects legacy method numbers, so integrators have to
+    /// migrate to the FRC-42 ones. Set at construction.
+    pub reject_legacy_methods: bool,
 }
 
-/// We should probably have a derive macro to mark an object as a state object,
-/// and have load and save methods automatically generated for them as part of a
-/// StateObject trait (i.e. impl StateObject for State).
-impl State {
-    pub fn load() -> Self {
-        // First, load the current state root.
-        let root = match sdk::sself::root() {
-            Ok(root) => root,
-            Err(err) => abort!(USR_ILLEGAL_STATE, "failed to get root: {:?}", err),
-        };
-
-        // Load the actor state from the state tree.
-        match Blockstore.get_cbor::<Self>(&root) {
-            Ok(Some(state)) => state,
-            Ok(None) => abort!(USR_ILLEGAL_STATE, "state does not exist"),
-            Err(err) => abort!(USR_ILLEGAL_STATE, "failed to get state: {}", err),
+impl Default for State {
+    fn default() -> Self {
+        State {
+            version: manifest::STATE_VERSION,
+            count: 0,
+            reject_legacy_methods: false,
         }
     }
+}
 
-    pub fn save(&self) -> Cid {
-        let serialized = match to_vec(self) {
-            Ok(s) => s,
-            Err(err) => abort!(USR_SERIALIZATION, "failed to serialize state: {:?}", err),
-        };
-        let cid = match sdk::ipld::put(Code::Blake2b256.into(), 32, DAG_CBOR, serialized.as_slice())
-        {
-            Ok(cid) => cid,
-            Err(err) => abort!(USR_SERIALIZATION, "failed to store initial state: {:}", err),
-        };
-        if let Err(err) = sdk::sself::set_root(&cid) {
-            abort!(USR_ILLEGAL_STATE, "failed to set root ciid: {:}", err);
-        }
-        cid
+impl StateConfig for State {
+    type Store = blockstore::Blockstore;
+
+    fn store() -> Self::Store {
+        blockstore::Blockstore
+    }
+
+    fn decode(raw: &[u8]) -> Result<Self> {
+        migrations::decode(raw)
     }
 }
 
+/// An entry in the actor's dispatch table.
+pub struct Method {
+    /// The method number messages must carry to invoke this method.
+    pub number: MethodNum,
+    /// The FRC-42 method name the number is derived from.
+    pub name: &'static str,
+    /// Whether `number` is a pre-FRC-42 number, kept as an alias so existing
+    /// tooling keeps working, rather than one derived from `name`.
+    pub legacy: bool,
+    /// The `INTERFACE_VERSION` the method first appeared in. Tooling uses this to
+    /// feature-detect what a deployment supports.
+    pub since: u64,
+    /// The handler. It takes the ID of the parameters block and returns the
+    /// return value, if any.
+    pub handler: fn(u32) -> Option<RawBytes>,
+}
+
+/// Generates the `METHODS` dispatch table from a list of actor methods.
+///
+/// Each entry reads `NUMBER => Name since VERSION: handler(PARAMS) -> RET;`:
+///
+/// - A leading `legacy` marks a pre-FRC-42 number kept as an alias.
+/// - `PARAMS` is empty for methods without params, `T` for required params,
+///   or `Option<T>` for optional ones. They are decoded with the `params`
+///   module, so missing or malformed params abort uniformly.
+/// - `-> RET` is omitted for methods without a return value; otherwise the
+///   handler's return value is serialized as the method's return block.
+///
+/// Outer attributes (e.g. `#[cfg(...)]`) are applied to the table entry.
+macro_rules! methods {
+    ($(
+        $(#[$attr:meta])*
+        $($legacy:ident)? $num:literal => $name:ident since $since:literal:
+            $($handler:ident)::+ ($($params:tt)*) $(-> $ret:ty)?;
+    )*) => {
+        /// The method number -> handler mapping used by `invoke`.
+        ///
+        /// This is the single source of truth for dispatch; native simulators
+        /// and fuzzers should look methods up here rather than duplicating the
+        /// mapping. It doubles as the changelogged method registry served by
+        /// `get_interface`, so new entries must record the interface version
+        /// they ship in.
+        pub const METHODS: &[Method] = &[$(
+            $(#[$attr])*
+            Method {
+                number: $num,
+                name: stringify!($name),
+                legacy: methods!(@legacy $($legacy)?),
+                since: $since,
+                handler: |_params| {
+                    methods!(@ret
+                        methods!(@call $($handler)::+, _params, stringify!($name), ($($params)*))
+                        $(=> $ret)?
+                    )
+                },
+            },
+        )*];
+    };
+
+    (@legacy) => { false };
+    (@legacy legacy) => { true };
+
+    (@call $($handler:ident)::+, $p:ident, $name:expr, ()) => {
+        $($handler)::+()
+    };
+    (@call $($handler:ident)::+, $p:ident, $name:expr, (Option<$t:ty>)) => {
+        $($handler)::+($crate::params::deserialize_optional_params::<$t>($p, $name))
+    };
+    (@call $($handler:ident)::+, $p:ident, $name:expr, ($t:ty)) => {
+        $($handler)::+($crate::params::deserialize_params::<$t>($p, $name))
+    };
+
+    (@ret $call:expr) => {{
+        $call;
+        None
+    }};
+    (@ret $call:expr => $ret:ty) => {
+        match RawBytes::serialize::<$ret>($call) {
+            Ok(ret) => Some(ret),
+            Err(err) => abort!(
+                USR_SERIALIZATION,
+                "failed to serialize return value: {:?}",
+                err
+            ),
+        }
+    };
+}
+
+/// The version of the method table. Bump it whenever a method is added,
+/// removed or renumbered, or its params or return value change, and record
+/// the new version as `since` on the affected entries. It is independent of
+/// the state layout version.
+///
+/// 1. The original actor: the constructor, without params, and SayHello as
+///    method 2.
+/// 2. FRC-42 method numbers, keeping method 2 as a legacy alias; adds
+///    GetVersion, GetInterface (listing methods and actor exit codes) and
+///    optional constructor params, which can turn the legacy alias off.
+pub const INTERFACE_VERSION: u64 = 2;
+
+// Method numbers follow FRC-42: the first big-endian u32 chunk of
+// blake2b-512("1|" + name) that is >= 2^24. The constructor keeps method
+// number 1, as required by InitActor#Exec.
+methods! {
+    1 => Constructor since 2: constructor(Option<ConstructorParams>);
+    3310437020 => SayHello since 2: say_hello() -> String;
+    2755660762 => GetVersion since 2: get_version() -> Manifest;
+    3090884168 => GetInterface since 2: get_interface() -> Interface;
+    #[cfg(feature = "testing")]
+    607853351 => SetCount since 2: testing::set_count(u64);
+
+    // SayHello's number from before the switch to FRC-42.
+    legacy 2 => SayHello since 1: say_hello() -> String;
+}
+
+/// An entry of the `get_interface` return value.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct MethodInfo {
+    pub number: MethodNum,
+    pub name: String,
+    pub legacy: bool,
+    pub since: u64,
+}
+
+/// An entry of the `get_interface` return value.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ExitCodeInfo {
+    pub code: u32,
+    pub name: String,
+    pub retryable: bool,
+}
+
+/// The return value of `get_interface`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct Interface {
+    pub interface_version: u64,
+    pub state_version: u64,
+    pub methods: Vec<MethodInfo>,
+    pub exit_codes: Vec<ExitCodeInfo>,
+}
+
+/// Looks up a method in the dispatch table by number.
+pub fn method(number: MethodNum) -> Option<&'static Method> {
+    METHODS.iter().find(|m| m.number == number)
+}
+
 /// The actor's WASM entrypoint. It takes the ID of the parameters block,
 /// and returns the ID of the return value block, or NO_DATA_BLOCK_ID if no
 /// return value.
 ///
-/// Should probably have macros similar to the ones on fvm.filecoin.io snippets.
-/// Put all methods inside an impl struct and annotate it with a derive macro
-/// that handles state serde and dispatch.
+/// Dispatch goes through the `METHODS` table generated by `methods!`, which
+/// also handles params deserialization and return value serialization.
 #[no_mangle]
-pub fn invoke(_: u32) -> u32 {
+pub fn invoke(params: u32) -> u32 {
     // Conduct method dispatch. Handle input parameters and return data.
-    let ret: Option<RawBytes> = match sdk::message::method_number() {
-        1 => constructor(),
-        2 => say_hello(),
-        _ => abort!(USR_UNHANDLED_MESSAGE, "unrecognized method"),
+    let ret: Option<RawBytes> = match method(sdk::message::method_number()) {
+        Some(m) if m.legacy && State::load().reject_legacy_methods => abort!(
+            USR_UNHANDLED_MESSAGE,
+            "legacy method number {} is disabled; use {}'s FRC-42 number",
+            m.number,
+            m.name
+        ),
+        Some(m) => (m.handler)(params),
+        None => abort!(USR_UNHANDLED_MESSAGE, "unrecognized method"),
     };
+    runtime::assert_caller_validated();
 
     // Insert the return data block if necessary, and return the correct
     // block ID.
@@ -89,41 +258,95 @@ pub fn invoke(_: u32) -> u32 {
     }
 }
 
+/// Optional constructor parameters.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct ConstructorParams {
+    /// CID of a DAG_CBOR-encoded `State` block the deployer staged in the
+    /// state tree beforehand. Used as the initial state instead of the default,
+    /// which keeps large initial configurations out of the Exec params.
+    pub initial_state: Option<Cid>,
+    /// Opts into strict mode: legacy method numbers are rejected with
+    /// USR_UNHANDLED_MESSAGE. Overrides the flag in `initial_state`.
+    pub reject_legacy_methods: bool,
+}
+
 /// The constructor populates the initial state.
 ///
 /// Method num 1. This is part of the Filecoin calling convention.
 /// InitActor#Exec will call the constructor on method_num = 1.
-pub fn constructor() -> Option<RawBytes> {
-    // This constant should be part of the SDK.
-    const INIT_ACTOR_ADDR: ActorID = 1;
-
-    // Should add SDK sugar to perform ACL checks more succinctly.
-    // i.e. the equivalent of the validate_* builtin-actors runtime methods.
-    // https://github.com/filecoin-project/builtin-actors/blob/master/actors/runtime/src/runtime/fvm.rs#L110-L146
-    if sdk::message::caller() != INIT_ACTOR_ADDR {
-        abort!(USR_FORBIDDEN, "constructor invoked by non-init actor");
-    }
+pub fn constructor(params: Option<ConstructorParams>) {
+    validate_immediate_caller_is(&[INIT_ACTOR_ADDR]);
 
-    let state = State::default();
+    let p = params.unwrap_or(ConstructorParams {
+        initial_state: None,
+        reject_legacy_methods: false,
+    });
+    let mut state = match p.initial_state {
+        None => State::default(),
+        Some(cid) => match State::get(&cid) {
+            Ok(Some(state)) => state,
+            Ok(None) => abort!(USR_ILLEGAL_ARGUMENT, "initial state {} does not exist", cid),
+            Err(err) => abort!(
+                USR_ILLEGAL_ARGUMENT,
+                "failed to load initial state {}: {}",
+                cid,
+                err
+            ),
+        },
+    };
+    state.reject_legacy_methods = p.reject_legacy_methods;
     state.save();
-    None
 }
 
-/// Method num 2.
-pub fn say_hello() -> Option<RawBytes> {
-    let mut state = State::load();
-    state.count += 1;
-    state.save();
+/// FRC-42 method `SayHello`.
+pub fn say_hello() -> String {
+    validate_immediate_caller_accept_any();
 
-    let ret = to_vec(format!("Hello world #{}!", &state.count).as_str());
-    match ret {
-        Ok(ret) => Some(RawBytes::new(ret)),
-        Err(err) => {
-            abort!(
-                USR_ILLEGAL_STATE,
-                "failed to serialize return value: {:?}",
-                err
-            );
-        }
+    let count = match State::transaction(|state| {
+        state.count += 1;
+        Ok(state.count)
+    }) {
+        Ok(count) => count,
+        Err(err) => abort!(USR_ILLEGAL_STATE, "failed to update state: {}", err),
+    };
+
+    format!("Hello world #{}!", count)
+}
+
+/// FRC-42 method `GetVersion`.
+///
+/// Returns the deployment manifest (crate version, git commit, enabled
+/// features and state version) this code was built with.
+pub fn get_version() -> Manifest {
+    validate_immediate_caller_accept_any();
+    Manifest::current()
+}
+
+/// FRC-42 method `GetInterface`.
+///
+/// Returns the method registry, so mixed-version tooling can feature-detect
+/// what this deployment supports instead of trial-and-erroring calls.
+pub fn get_interface() -> Interface {
+    validate_immediate_caller_accept_any();
+    Interface {
+        interface_version: INTERFACE_VERSION,
+        state_version: manifest::STATE_VERSION,
+        methods: METHODS
+            .iter()
+            .map(|m| MethodInfo {
+                number: m.number,
+                name: m.name.to_owned(),
+                legacy: m.legacy,
+                since: m.since,
+            })
+            .collect(),
+        exit_codes: EXIT_CODES
+            .iter()
+            .map(|e| ExitCodeInfo {
+                code: e.code.value(),
+                name: e.name.to_owned(),
+                retryable: e.retryable,
+            })
+            .collect(),
     }
 }
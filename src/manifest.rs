@@ -0,0 +1,26 @@
+//! The deployment manifest, generated by `build.rs` and embedded in the wasm.
+
+use fvm_ipld_encoding::tuple::{Deserialize_tuple, Serialize_tuple};
+
+include!(concat!(env!("OUT_DIR"), "/manifest.rs"));
+
+/// The return value of `get_version`.
+#[derive(Serialize_tuple, Deserialize_tuple, Clone, Debug)]
+pub struct Manifest {
+    pub crate_version: String,
+    pub git_commit: String,
+    pub features: Vec<String>,
+    pub state_version: u64,
+}
+
+impl Manifest {
+    /// The manifest of the running build.
+    pub fn current() -> Self {
+        Manifest {
+            crate_version: CRATE_VERSION.to_owned(),
+            git_commit: GIT_COMMIT.to_owned(),
+            features: FEATURES.iter().map(|f| f.to_string()).collect(),
+            state_version: STATE_VERSION,
+        }
+    }
+}
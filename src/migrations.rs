@@ -0,0 +1,118 @@
+//! Upgrades of older state layouts to the current `State`.
+//!
+//! Every change to the layout of `State` bumps `STATE_VERSION` in `build.rs`
+//! and adds a frozen copy of the previous layout here, together with a
+//! function upgrading it by one version. `StateObject::get` decodes stored
+//! objects through `decode`, so deployed instances keep working across
+//! upgrades; the upgraded state is persisted on the next save.
+
+use anyhow::{anyhow, Result};
+use fvm_ipld_encoding::from_slice;
+use fvm_ipld_encoding::tuple::Deserialize_tuple;
+
+use crate::manifest::STATE_VERSION;
+use crate::State;
+
+/// State layout v2: the counter with a version field.
+const V2: u64 = 2;
+
+/// State layout v3: adds the strict-mode flag.
+const V3: u64 = 3;
+
+// Bumping STATE_VERSION without adding the upgrade from v3 would label states
+// as the new version without migrating them.
+const _: () = assert!(STATE_VERSION == V3, "add a migration from v3");
+
+/// State layout v1: the bare counter, before the version field existed.
+#[derive(Deserialize_tuple)]
+struct StateV1 {
+    count: u64,
+}
+
+/// State layout v2.
+#[derive(Deserialize_tuple)]
+struct StateV2 {
+    version: u64,
+    count: u64,
+}
+
+/// Decodes a serialized state object of any known layout, upgrading it to
+/// the current one.
+pub fn decode(raw: &[u8]) -> Result<State> {
+    // v1 predates the version field, so it can only be recognized by shape.
+    if let Ok(v1) = from_slice::<StateV1>(raw) {
+        return Ok(v2_to_v3(v1_to_v2(v1)));
+    }
+    if let Ok(v2) = from_slice::<StateV2>(raw) {
+        if v2.version != V2 {
+            return Err(anyhow!("unsupported state version {}", v2.version));
+        }
+        return Ok(v2_to_v3(v2));
+    }
+
+    let state: State = from_slice(raw)?;
+    if state.version != STATE_VERSION {
+        return Err(anyhow!(
+            "unsupported state version {} (expected {})",
+            state.version,
+            STATE_VERSION
+        ));
+    }
+    Ok(state)
+}
+
+fn v1_to_v2(v1: StateV1) -> StateV2 {
+    StateV2 {
+        version: V2,
+        count: v1.count,
+    }
+}
+
+fn v2_to_v3(v2: StateV2) -> State {
+    State {
+        version: V3,
+        count: v2.count,
+        reject_legacy_methods: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fvm_ipld_encoding::to_vec;
+
+    use super::*;
+
+    fn fields(state: State) -> (u64, u64, bool) {
+        (state.version, state.count, state.reject_legacy_methods)
+    }
+
+    #[test]
+    fn upgrades_v1() {
+        let state = decode(&to_vec(&(7u64,)).unwrap()).unwrap();
+        assert_eq!(fields(state), (3, 7, false));
+    }
+
+    #[test]
+    fn upgrades_v2() {
+        let state = decode(&to_vec(&(2u64, 7u64)).unwrap()).unwrap();
+        assert_eq!(fields(state), (3, 7, false));
+    }
+
+    #[test]
+    fn decodes_v3_unchanged() {
+        let state = decode(&to_vec(&(3u64, 7u64, true)).unwrap()).unwrap();
+        assert_eq!(fields(state), (3, 7, true));
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        assert!(decode(&to_vec(&(3u64, 7u64)).unwrap()).is_err());
+        assert!(decode(&to_vec(&(4u64, 7u64, false)).unwrap()).is_err());
+    }
+
+    #[test]
+    fn rejects_other_shapes() {
+        assert!(decode(&to_vec(&(3u64, 7u64, false, 0u64)).unwrap()).is_err());
+        assert!(decode(&to_vec(&()).unwrap()).is_err());
+    }
+}
@@ -0,0 +1,67 @@
+//! Uniform handling of method parameters.
+
+use fvm_ipld_encoding::{from_slice, DAG_CBOR};
+use fvm_sdk as sdk;
+use fvm_sdk::NO_DATA_BLOCK_ID;
+use serde::de::DeserializeOwned;
+
+use crate::abort;
+
+/// The CBOR encoding of `null`.
+const CBOR_NULL: u8 = 0xf6;
+
+/// Reads and deserializes the parameters of a method that requires them.
+///
+/// A missing params block, an empty one, and CBOR `null` are all treated as
+/// "no params" and abort with USR_ILLEGAL_ARGUMENT naming the method, so
+/// callers see the same exit code whichever way they left params out.
+pub fn deserialize_params<T: DeserializeOwned>(params: u32, method: &str) -> T {
+    match deserialize_optional_params(params, method) {
+        Some(p) => p,
+        None => abort!(USR_ILLEGAL_ARGUMENT, "{}: params are required", method),
+    }
+}
+
+/// Reads and deserializes the parameters of a method that may be called
+/// without them, returning `None` for a missing block, an empty one, or CBOR
+/// `null`.
+///
+/// Params must be DAG_CBOR; any other codec aborts with USR_SERIALIZATION
+/// before decoding is attempted, so e.g. raw bytes that happen to parse as
+/// CBOR are never misinterpreted. An unreadable params block or one that
+/// fails to decode aborts with USR_SERIALIZATION too, never with a panic.
+pub fn deserialize_optional_params<T: DeserializeOwned>(params: u32, method: &str) -> Option<T> {
+    if params == NO_DATA_BLOCK_ID {
+        return None;
+    }
+    let (codec, raw) = match sdk::message::params_raw(params) {
+        Ok(p) => p,
+        Err(err) => abort!(
+            USR_SERIALIZATION,
+            "{}: failed to read params block {}: {:?}",
+            method,
+            params,
+            err
+        ),
+    };
+    if codec != DAG_CBOR {
+        abort!(
+            USR_SERIALIZATION,
+            "{}: params must be DAG_CBOR, got codec {:#x}",
+            method,
+            codec
+        );
+    }
+    if raw.is_empty() || raw == [CBOR_NULL] {
+        return None;
+    }
+    match from_slice(&raw) {
+        Ok(p) => Some(p),
+        Err(err) => abort!(
+            USR_SERIALIZATION,
+            "{}: failed to deserialize params: {:?}",
+            method,
+            err
+        ),
+    }
+}
@@ -0,0 +1,81 @@
+//! Caller validation, mirroring the `validate_immediate_caller_*` methods of
+//! the builtin-actors runtime.
+//!
+//! Every method must validate its caller exactly once, even if only to accept
+//! any caller; `invoke` aborts if a method returns without doing so. This
+//! makes a forgotten access check fail loudly instead of silently letting
+//! everyone in.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use cid::Cid;
+use fvm_sdk as sdk;
+use fvm_shared::address::Address;
+
+use crate::abort;
+
+// The FVM instantiates the actor's wasm afresh for every invocation, so
+// statics start from their initial value on each message and can hold
+// per-invocation state without being reset. Other modules rely on this too.
+static CALLER_VALIDATED: AtomicBool = AtomicBool::new(false);
+
+/// The init actor, which calls constructors on InitActor#Exec.
+/// This constant should be part of the SDK.
+pub const INIT_ACTOR_ADDR: Address = Address::new_id(1);
+
+/// Accepts any caller.
+pub fn validate_immediate_caller_accept_any() {
+    assert_not_validated();
+}
+
+/// Aborts with USR_FORBIDDEN unless the caller is one of `addresses`.
+pub fn validate_immediate_caller_is(addresses: &[Address]) {
+    assert_not_validated();
+
+    let caller = sdk::message::caller();
+    let allowed = addresses
+        .iter()
+        .any(|a| sdk::actor::resolve_address(a) == Some(caller));
+    if !allowed {
+        abort!(
+            USR_FORBIDDEN,
+            "caller f0{} is not one of {:?}",
+            caller,
+            addresses
+        );
+    }
+}
+
+/// Aborts with USR_FORBIDDEN unless the caller's code CID is one of `types`.
+pub fn validate_immediate_caller_type(types: &[Cid]) {
+    assert_not_validated();
+
+    let caller = sdk::message::caller();
+    let code = match sdk::actor::get_actor_code_cid(&Address::new_id(caller)) {
+        Some(code) => code,
+        None => abort!(USR_FORBIDDEN, "no code for caller f0{}", caller),
+    };
+    if !types.contains(&code) {
+        abort!(
+            USR_FORBIDDEN,
+            "caller f0{} has code {}, which is not one of {:?}",
+            caller,
+            code,
+            types
+        );
+    }
+}
+
+/// Aborts unless the current method validated its caller. Called by `invoke`
+/// after the method returns.
+pub fn assert_caller_validated() {
+    if !CALLER_VALIDATED.load(Ordering::Relaxed) {
+        abort!(USR_ASSERTION_FAILED, "method did not validate its caller");
+    }
+}
+
+fn assert_not_validated() {
+    if CALLER_VALIDATED.swap(true, Ordering::Relaxed) {
+        abort!(USR_ASSERTION_FAILED, "caller validated more than once");
+    }
+}
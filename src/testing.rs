@@ -0,0 +1,25 @@
+//! Methods that only exist in builds with the `testing` feature enabled.
+//!
+//! They let integration tests put the actor into a given state directly instead
+//! of replaying the messages that would lead there. Release builds must never
+//! enable this feature; `get_version` reports the enabled features so a
+//! deployed build can be checked.
+
+use state_object::StateObject;
+
+use crate::runtime::validate_immediate_caller_accept_any;
+use crate::{abort, State};
+
+/// FRC-42 method `SetCount`.
+///
+/// Overwrites the counter with the `u64` passed as params.
+pub fn set_count(count: u64) {
+    validate_immediate_caller_accept_any();
+
+    if let Err(err) = State::transaction(|state| {
+        state.count = count;
+        Ok(())
+    }) {
+        abort!(USR_ILLEGAL_STATE, "failed to update state: {}", err);
+    }
+}
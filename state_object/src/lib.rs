@@ -0,0 +1,83 @@
+//! Actor state objects persisted under the actor's state root.
+//!
+//! `#[derive(StateObject)]` generates the `StateObject` implementation; the
+//! type supplies its blockstore and decoding through `StateConfig`. Generated
+//! code only refers to this crate, so actors can lay out their own modules
+//! however they like.
+
+use anyhow::Result;
+use cid::Cid;
+use fvm_ipld_blockstore::Blockstore;
+use fvm_ipld_encoding::de::DeserializeOwned;
+
+pub use state_object_derive::StateObject;
+
+/// A macro to abort concisely.
+/// This should be part of the SDK as it's very handy.
+#[macro_export]
+macro_rules! abort {
+    ($code:ident, $msg:literal $(, $ex:expr)*) => {
+        $crate::__private::fvm_sdk::vm::abort(
+            $crate::__private::fvm_shared::error::ExitCode::$code.value(),
+            Some(format!($msg, $($ex,)*).as_str()),
+        )
+    };
+}
+
+/// A state object, persisted DAG-CBOR encoded under the actor's state root.
+///
+/// Implement it with `#[derive(StateObject)]`, which generates `get`, `load`
+/// and `save`; the type must also implement `StateConfig`.
+pub trait StateObject: Sized {
+    /// Reads the object stored under `cid`.
+    fn get(cid: &Cid) -> Result<Option<Self>>;
+
+    /// Loads the object from the actor's state root, aborting on failure.
+    fn load() -> Self;
+
+    /// Stores the object and makes it the actor's state root, aborting on
+    /// failure.
+    fn save(&self) -> Cid;
+
+    /// Loads the state, runs `f` on it, and saves the result only if `f`
+    /// succeeds. Mutating methods should go through this rather than
+    /// hand-rolling load/mutate/save, so no step can be forgotten.
+    fn transaction<F, R>(f: F) -> Result<R>
+    where
+        F: FnOnce(&mut Self) -> Result<R>,
+    {
+        let mut state = Self::load();
+        let ret = f(&mut state)?;
+        state.save();
+        Ok(ret)
+    }
+}
+
+/// How a `#[derive(StateObject)]` type is stored.
+pub trait StateConfig: Sized {
+    /// The blockstore the object is read from and written to.
+    type Store: Blockstore;
+
+    /// Returns the blockstore to use for a single read or write.
+    fn store() -> Self::Store;
+
+    /// Decodes a stored object. Override this to upgrade objects written in
+    /// older layouts.
+    fn decode(raw: &[u8]) -> Result<Self>
+    where
+        Self: DeserializeOwned,
+    {
+        Ok(fvm_ipld_encoding::from_slice(raw)?)
+    }
+}
+
+/// Re-exports for the code generated by `abort!` and `#[derive(StateObject)]`.
+#[doc(hidden)]
+pub mod __private {
+    pub use anyhow;
+    pub use cid;
+    pub use fvm_ipld_blockstore;
+    pub use fvm_ipld_encoding;
+    pub use fvm_sdk;
+    pub use fvm_shared;
+}
@@ -0,0 +1,84 @@
+//! `#[derive(StateObject)]` for actor state types.
+//!
+//! Use it through the `state_object` crate, which re-exports it next to the
+//! traits it implements.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, DeriveInput};
+
+/// Derives `state_object::StateObject`, generating `get`, `load` and `save`
+/// for a type stored DAG-CBOR encoded under the actor's state root.
+///
+/// Reads and writes go through the type's `StateConfig::store`, and stored
+/// bytes are decoded with `StateConfig::decode`, so objects written in older
+/// layouts can be upgraded on read. Like hand-written actor methods, the
+/// generated code aborts the invocation on failure.
+#[proc_macro_derive(StateObject)]
+pub fn derive_state_object(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input).into()
+}
+
+fn expand(input: &DeriveInput) -> TokenStream2 {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    quote! {
+        impl #impl_generics ::state_object::StateObject for #name #ty_generics #where_clause {
+            fn get(
+                cid: &::state_object::__private::cid::Cid,
+            ) -> ::state_object::__private::anyhow::Result<Option<Self>> {
+                let store = <Self as ::state_object::StateConfig>::store();
+                match ::state_object::__private::fvm_ipld_blockstore::Blockstore::get(&store, cid)? {
+                    Some(raw) => Ok(Some(<Self as ::state_object::StateConfig>::decode(&raw)?)),
+                    None => Ok(None),
+                }
+            }
+
+            fn load() -> Self {
+                // First, load the current state root.
+                let root = match ::state_object::__private::fvm_sdk::sself::root() {
+                    Ok(root) => root,
+                    Err(err) => ::state_object::abort!(
+                        USR_ILLEGAL_STATE,
+                        "failed to get root: {:?}",
+                        err
+                    ),
+                };
+
+                // Load the actor state from the state tree.
+                match <Self as ::state_object::StateObject>::get(&root) {
+                    Ok(Some(state)) => state,
+                    Ok(None) => ::state_object::abort!(USR_ILLEGAL_STATE, "state does not exist"),
+                    Err(err) => ::state_object::abort!(
+                        USR_ILLEGAL_STATE,
+                        "failed to get state: {}",
+                        err
+                    ),
+                }
+            }
+
+            fn save(&self) -> ::state_object::__private::cid::Cid {
+                let store = <Self as ::state_object::StateConfig>::store();
+                let cid = match ::state_object::__private::fvm_ipld_encoding::CborStore::put_cbor(
+                    &store,
+                    self,
+                    ::state_object::__private::cid::multihash::Code::Blake2b256,
+                ) {
+                    Ok(cid) => cid,
+                    Err(err) => ::state_object::abort!(
+                        USR_SERIALIZATION,
+                        "failed to store state: {}",
+                        err
+                    ),
+                };
+                if let Err(err) = ::state_object::__private::fvm_sdk::sself::set_root(&cid) {
+                    ::state_object::abort!(USR_ILLEGAL_STATE, "failed to set root cid: {}", err);
+                }
+                cid
+            }
+        }
+    }
+}
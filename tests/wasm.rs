@@ -0,0 +1,561 @@
+//! Checks on the compiled actor wasm produced by wasm-builder.
+//!
+//! Methods are invoked under wasmtime with stub syscalls that implement just
+//! enough of the FVM to dispatch a message and capture its return block or
+//! exit code. Actor code can't be called natively: the syscalls are wasm
+//! imports.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::path::PathBuf;
+
+use cid::multihash::{Code, MultihashDigest};
+use cid::Cid;
+use fil_hello_world_actor::{
+    ConstructorParams, Interface, State, EXIT_CODES, EXIT_WRITE_BUDGET_EXCEEDED, INTERFACE_VERSION,
+};
+use fvm_ipld_encoding::{from_slice, to_vec, DAG_CBOR};
+use fvm_shared::error::{ErrorNumber, ExitCode};
+use fvm_shared::{ActorID, MethodNum};
+use wasmtime::{Caller, Engine, ExternType, Linker, Memory, Module, Store, Trap, Val};
+
+const CRATE_NAME: &str = "fil_hello_world_actor";
+
+const NO_DATA_BLOCK_ID: u32 = 0;
+
+const INIT_ACTOR: ActorID = 1;
+const CALLER: ActorID = 100;
+const RECEIVER: ActorID = 1000;
+
+const CONSTRUCTOR: MethodNum = 1;
+const SAY_HELLO: MethodNum = 3310437020;
+const LEGACY_SAY_HELLO: MethodNum = 2;
+const GET_INTERFACE: MethodNum = 3090884168;
+const SET_COUNT: MethodNum = 607853351;
+
+/// Returns the compacted wasm that `build.rs` produced for this test build.
+fn compact_wasm() -> Vec<u8> {
+    // Test binaries live in `<target>/<profile>/deps`, next to the `wbuild` output.
+    let profile_dir: PathBuf = std::env::current_exe()
+        .unwrap()
+        .ancestors()
+        .nth(2)
+        .unwrap()
+        .to_owned();
+    let path = profile_dir
+        .join("wbuild")
+        .join(CRATE_NAME)
+        .join(format!("{}.compact.wasm", CRATE_NAME));
+    std::fs::read(&path).unwrap_or_else(|e| panic!("failed to read {}: {}", path.display(), e))
+}
+
+/// Returns the contents of the custom section called `name`, if present.
+fn custom_section<'a>(mut wasm: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    assert_eq!(&wasm[..8], b"\0asm\x01\0\0\0", "not a wasm module");
+    wasm = &wasm[8..];
+    while !wasm.is_empty() {
+        let id = wasm[0];
+        wasm = &wasm[1..];
+        let size = read_leb128(&mut wasm) as usize;
+        let (mut section, rest) = wasm.split_at(size);
+        wasm = rest;
+        if id == 0 {
+            let len = read_leb128(&mut section) as usize;
+            if &section[..len] == name.as_bytes() {
+                return Some(&section[len..]);
+            }
+        }
+    }
+    None
+}
+
+fn read_leb128(data: &mut &[u8]) -> u64 {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = data[0];
+        *data = &data[1..];
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+/// An actor's persistent state: its state root and the blocks of its state
+/// tree. As in the FVM, invocations that abort leave it untouched.
+#[derive(Default, Clone)]
+struct Actor {
+    root: Option<Cid>,
+    store: HashMap<Cid, Vec<u8>>,
+}
+
+/// The state of a single invocation, shared with the stub syscalls.
+struct Invocation {
+    method: MethodNum,
+    caller: ActorID,
+    memory: Option<Memory>,
+    /// The actor's state, committed back only if the invocation succeeds.
+    actor: Actor,
+    /// Open blocks (codec, data), in order. Block IDs start at 1.
+    blocks: Vec<(u64, Vec<u8>)>,
+    /// The exit code passed to `vm::abort`, if the actor aborted.
+    exit_code: Option<u32>,
+}
+
+impl Actor {
+    /// Returns an actor whose constructor ran without params.
+    fn constructed() -> Actor {
+        let mut actor = Actor::default();
+        actor.invoke(INIT_ACTOR, CONSTRUCTOR, None).unwrap();
+        actor
+    }
+
+    /// Stages a DAG-CBOR block in the actor's state tree.
+    fn put(&mut self, data: Vec<u8>) -> Cid {
+        let cid = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(&data));
+        self.store.insert(cid, data);
+        cid
+    }
+
+    /// Decodes the state root.
+    fn state(&self) -> State {
+        from_slice(&self.store[&self.root.expect("no state root")]).unwrap()
+    }
+
+    /// Invokes `method` from `caller` on a fresh instance of the actor,
+    /// returning the return block, or the exit code if the actor aborted.
+    fn invoke(
+        &mut self,
+        caller: ActorID,
+        method: MethodNum,
+        params: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, ExitCode> {
+        let engine = Engine::default();
+        let module = Module::new(&engine, compact_wasm()).unwrap();
+        let mut blocks = Vec::new();
+        let params_id = match params {
+            Some(params) => {
+                blocks.push((DAG_CBOR, params));
+                blocks.len() as u32
+            }
+            None => NO_DATA_BLOCK_ID,
+        };
+        let mut store = Store::new(
+            &engine,
+            Invocation {
+                method,
+                caller,
+                memory: None,
+                actor: self.clone(),
+                blocks,
+                exit_code: None,
+            },
+        );
+        let mut linker = Linker::new(&engine);
+
+        for import in module.imports() {
+            let (module_name, name) = (
+                import.module().to_owned(),
+                import.name().unwrap().to_owned(),
+            );
+            match import.ty() {
+                ExternType::Memory(ty) => {
+                    let memory = Memory::new(&mut store, ty).unwrap();
+                    store.data_mut().memory = Some(memory);
+                    linker.define(&module_name, &name, memory).unwrap();
+                }
+                ExternType::Func(ty) => {
+                    linker
+                        .func_new(
+                            &module_name.clone(),
+                            &name.clone(),
+                            ty,
+                            move |caller, args, results| {
+                                results[0] =
+                                    Val::I32(syscall(caller, &module_name, &name, args)? as i32);
+                                Ok(())
+                            },
+                        )
+                        .unwrap();
+                }
+                other => panic!("unexpected import {:?}", other),
+            }
+        }
+
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+        let entrypoint = instance
+            .get_typed_func::<u32, u32, _>(&mut store, "invoke")
+            .unwrap();
+        let result = entrypoint.call(&mut store, params_id);
+        let invocation = store.into_data();
+        match result {
+            Ok(id) => {
+                *self = invocation.actor;
+                Ok(match id {
+                    NO_DATA_BLOCK_ID => None,
+                    id => Some(invocation.blocks[id as usize - 1].1.clone()),
+                })
+            }
+            Err(trap) => match invocation.exit_code {
+                Some(code) => Err(ExitCode::new(code)),
+                None => panic!("actor trapped without aborting: {}", trap),
+            },
+        }
+    }
+}
+
+/// Implements the syscalls the tested methods make. Syscalls return an error
+/// number, and write their result through a pointer passed as the first argument.
+fn syscall(
+    mut caller: Caller<'_, Invocation>,
+    module: &str,
+    name: &str,
+    args: &[Val],
+) -> Result<u32, Trap> {
+    let memory = caller.data().memory.unwrap();
+    let arg = |i: usize| args[i].unwrap_i32() as u32 as usize;
+    let read = |caller: &Caller<'_, Invocation>, offset: usize, len: usize| {
+        let mut buf = vec![0; len];
+        memory.read(caller, offset, &mut buf).unwrap();
+        buf
+    };
+    let read_cid = |caller: &Caller<'_, Invocation>, offset: usize| {
+        Cid::read_bytes(&memory.data(caller)[offset..]).unwrap()
+    };
+    let write = |caller: &mut Caller<'_, Invocation>, offset: usize, data: &[u8]| {
+        memory.write(caller, offset, data).unwrap()
+    };
+
+    match (module, name) {
+        ("vm", "context") => {
+            // InvocationContext, packed: value_received (lo, hi), caller,
+            // receiver, method_number, network_curr_epoch, network_version.
+            let mut context = Vec::new();
+            context.extend(0u128.to_le_bytes());
+            context.extend(caller.data().caller.to_le_bytes());
+            context.extend(RECEIVER.to_le_bytes());
+            context.extend(caller.data().method.to_le_bytes());
+            context.extend(0i64.to_le_bytes());
+            context.extend(16u32.to_le_bytes());
+            write(&mut caller, arg(0), &context);
+            Ok(0)
+        }
+        ("vm", "abort") => {
+            let message = read(&caller, arg(1), arg(2));
+            caller.data_mut().exit_code = Some(arg(0) as u32);
+            Err(Trap::new(String::from_utf8_lossy(&message)))
+        }
+        ("ipld", "block_create") => {
+            let codec = args[1].unwrap_i64() as u64;
+            let data = read(&caller, arg(2), arg(3));
+            caller.data_mut().blocks.push((codec, data));
+            let id = caller.data().blocks.len() as u32;
+            write(&mut caller, arg(0), &id.to_le_bytes());
+            Ok(0)
+        }
+        ("ipld", "block_link") => {
+            assert_eq!(args[2].unwrap_i64() as u64, u64::from(Code::Blake2b256));
+            let (codec, data) = caller.data().blocks[arg(1) - 1].clone();
+            let cid = Cid::new_v1(codec, Code::Blake2b256.digest(&data));
+            caller.data_mut().actor.store.insert(cid, data);
+            let bytes = cid.to_bytes();
+            write(&mut caller, arg(4), &bytes);
+            write(&mut caller, arg(0), &(bytes.len() as u32).to_le_bytes());
+            Ok(0)
+        }
+        ("ipld", "block_open") => {
+            let cid = read_cid(&caller, arg(1));
+            let data = match caller.data().actor.store.get(&cid) {
+                Some(data) => data.clone(),
+                None => return Ok(ErrorNumber::NotFound as u32),
+            };
+            let size = data.len() as u32;
+            caller.data_mut().blocks.push((cid.codec(), data));
+            let id = caller.data().blocks.len() as u32;
+            // IpldOpen, packed: codec, id, size.
+            let mut open = Vec::new();
+            open.extend(cid.codec().to_le_bytes());
+            open.extend(id.to_le_bytes());
+            open.extend(size.to_le_bytes());
+            write(&mut caller, arg(0), &open);
+            Ok(0)
+        }
+        ("ipld", "block_stat") => {
+            let (codec, data) = caller.data().blocks[arg(1) - 1].clone();
+            // IpldStat, packed: codec, size.
+            let mut stat = Vec::new();
+            stat.extend(codec.to_le_bytes());
+            stat.extend((data.len() as u32).to_le_bytes());
+            write(&mut caller, arg(0), &stat);
+            Ok(0)
+        }
+        ("ipld", "block_read") => {
+            let data = caller.data().blocks[arg(1) - 1].1.clone();
+            let (offset, max_len) = (arg(2), arg(4));
+            let rest = &data[offset.min(data.len())..];
+            write(&mut caller, arg(3), &rest[..rest.len().min(max_len)]);
+            // The number of bytes left over, negative if the buffer wasn't filled.
+            let remaining = rest.len() as i32 - max_len as i32;
+            write(&mut caller, arg(0), &remaining.to_le_bytes());
+            Ok(0)
+        }
+        ("self", "root") => {
+            let root = match caller.data().actor.root {
+                Some(root) => root.to_bytes(),
+                None => return Ok(ErrorNumber::IllegalOperation as u32),
+            };
+            write(&mut caller, arg(1), &root);
+            write(&mut caller, arg(0), &(root.len() as u32).to_le_bytes());
+            Ok(0)
+        }
+        ("self", "set_root") => {
+            let cid = read_cid(&caller, arg(0));
+            assert!(
+                caller.data().actor.store.contains_key(&cid),
+                "root {} is not stored",
+                cid
+            );
+            caller.data_mut().actor.root = Some(cid);
+            Ok(0)
+        }
+        // Only non-ID addresses need resolving, and the tests use none.
+        ("actor", "resolve_address") => Ok(ErrorNumber::NotFound as u32),
+        // Logging is best-effort.
+        ("debug", _) => Ok(0),
+        _ => Err(Trap::new(format!(
+            "unexpected syscall {}::{}",
+            module, name
+        ))),
+    }
+}
+
+fn get_interface() -> Interface {
+    let ret = Actor::default()
+        .invoke(CALLER, GET_INTERFACE, None)
+        .unwrap()
+        .expect("no return value");
+    from_slice(&ret).unwrap()
+}
+
+/// Computes the FRC-42 method number for `name`: the first big-endian u32 chunk
+/// of blake2b-512("1|" + name) that is >= 2^24.
+fn frc42_method_number(name: &str) -> MethodNum {
+    let digest = Code::Blake2b512.digest(format!("1|{}", name).as_bytes());
+    digest
+        .digest()
+        .chunks(4)
+        .map(|chunk| u32::from_be_bytes(chunk.try_into().unwrap()))
+        .find(|&n| n >= 1 << 24)
+        .expect("no FRC-42 method number in digest") as MethodNum
+}
+
+#[test]
+fn method_numbers_match_frc42() {
+    let interface = get_interface();
+    for m in interface
+        .methods
+        .iter()
+        .filter(|m| !m.legacy && m.number != 1)
+    {
+        assert_eq!(m.number, frc42_method_number(&m.name), "{}", m.name);
+    }
+}
+
+#[test]
+fn legacy_numbers_alias_frc42_methods() {
+    let interface = get_interface();
+    for m in interface.methods.iter().filter(|m| m.legacy) {
+        assert!(
+            interface
+                .methods
+                .iter()
+                .any(|other| !other.legacy && other.name == m.name),
+            "legacy method {} has no FRC-42 number",
+            m.name
+        );
+    }
+    for (i, m) in interface.methods.iter().enumerate() {
+        assert!(
+            interface.methods[i + 1..]
+                .iter()
+                .all(|other| other.number != m.number),
+            "method number {} is used twice",
+            m.number
+        );
+    }
+}
+
+#[test]
+fn since_is_an_interface_version() {
+    let interface = get_interface();
+    assert_eq!(interface.interface_version, INTERFACE_VERSION);
+    for m in &interface.methods {
+        assert!(
+            (1..=INTERFACE_VERSION).contains(&m.since),
+            "{} ({}) has since {}",
+            m.name,
+            m.number,
+            m.since
+        );
+    }
+}
+
+fn say_hello(actor: &mut Actor, method: MethodNum) -> String {
+    let ret = actor.invoke(CALLER, method, None).unwrap();
+    from_slice(&ret.expect("no return value")).unwrap()
+}
+
+#[test]
+fn interface_lists_actor_exit_codes() {
+    let interface = get_interface();
+    assert_eq!(interface.exit_codes.len(), EXIT_CODES.len());
+    for (info, code) in interface.exit_codes.iter().zip(EXIT_CODES) {
+        assert_eq!(
+            (info.code, info.name.as_str(), info.retryable),
+            (code.code.value(), code.name, code.retryable)
+        );
+    }
+    assert!(interface
+        .exit_codes
+        .iter()
+        .any(|e| e.code == EXIT_WRITE_BUDGET_EXCEEDED.value()
+            && e.name == "EXIT_WRITE_BUDGET_EXCEEDED"));
+}
+
+#[test]
+fn say_hello_increments_the_count() {
+    let mut actor = Actor::constructed();
+    assert_eq!(say_hello(&mut actor, SAY_HELLO), "Hello world #1!");
+    assert_eq!(say_hello(&mut actor, SAY_HELLO), "Hello world #2!");
+    assert_eq!(actor.state().count, 2);
+}
+
+#[test]
+fn v1_state_is_upgraded_on_load() {
+    let mut actor = Actor::default();
+    actor.root = Some(actor.put(to_vec(&(41u64,)).unwrap()));
+
+    assert_eq!(say_hello(&mut actor, SAY_HELLO), "Hello world #42!");
+    let state = actor.state();
+    assert_eq!((state.version, state.count), (3, 42));
+    assert!(!state.reject_legacy_methods);
+}
+
+#[test]
+fn constructor_uses_initial_state() {
+    let mut actor = Actor::default();
+    let initial_state = actor.put(
+        to_vec(&State {
+            count: 41,
+            ..Default::default()
+        })
+        .unwrap(),
+    );
+    let params = to_vec(&ConstructorParams {
+        initial_state: Some(initial_state),
+        reject_legacy_methods: false,
+    })
+    .unwrap();
+    actor.invoke(INIT_ACTOR, CONSTRUCTOR, Some(params)).unwrap();
+
+    assert_eq!(actor.root, Some(initial_state));
+    assert_eq!(say_hello(&mut actor, SAY_HELLO), "Hello world #42!");
+}
+
+#[test]
+fn constructor_rejects_missing_initial_state() {
+    let mut actor = Actor::default();
+    let initial_state = Cid::new_v1(DAG_CBOR, Code::Blake2b256.digest(b"missing"));
+    let params = to_vec(&ConstructorParams {
+        initial_state: Some(initial_state),
+        reject_legacy_methods: false,
+    })
+    .unwrap();
+
+    assert_eq!(
+        actor.invoke(INIT_ACTOR, CONSTRUCTOR, Some(params)),
+        Err(ExitCode::USR_ILLEGAL_ARGUMENT)
+    );
+    assert_eq!(actor.root, None);
+}
+
+#[test]
+fn constructor_rejects_other_callers() {
+    let mut actor = Actor::default();
+    assert_eq!(
+        actor.invoke(CALLER, CONSTRUCTOR, None),
+        Err(ExitCode::USR_FORBIDDEN)
+    );
+    assert_eq!(actor.root, None);
+}
+
+#[test]
+fn legacy_numbers_dispatch() {
+    let mut actor = Actor::constructed();
+    assert_eq!(say_hello(&mut actor, LEGACY_SAY_HELLO), "Hello world #1!");
+    assert_eq!(say_hello(&mut actor, SAY_HELLO), "Hello world #2!");
+}
+
+#[test]
+fn strict_mode_rejects_legacy_numbers() {
+    let mut actor = Actor::default();
+    let params = to_vec(&ConstructorParams {
+        initial_state: None,
+        reject_legacy_methods: true,
+    })
+    .unwrap();
+    actor.invoke(INIT_ACTOR, CONSTRUCTOR, Some(params)).unwrap();
+
+    assert_eq!(
+        actor.invoke(CALLER, LEGACY_SAY_HELLO, None),
+        Err(ExitCode::USR_UNHANDLED_MESSAGE)
+    );
+    assert_eq!(say_hello(&mut actor, SAY_HELLO), "Hello world #1!");
+}
+
+/// The testing methods must not ship in builds without the `testing` feature.
+/// `get_interface` serves the `METHODS` table, so this covers both dispatch
+/// and the registry.
+#[test]
+#[cfg(not(feature = "testing"))]
+fn release_build_has_no_testing_methods() {
+    let interface = get_interface();
+    assert!(interface.methods.iter().all(|m| m.name != "SetCount"));
+    assert!(interface.methods.iter().all(|m| m.number != SET_COUNT));
+    assert_eq!(
+        Actor::constructed().invoke(CALLER, SET_COUNT, None),
+        Err(ExitCode::USR_UNHANDLED_MESSAGE)
+    );
+}
+
+#[test]
+#[cfg(feature = "testing")]
+fn testing_build_has_testing_methods() {
+    let interface = get_interface();
+    assert!(interface
+        .methods
+        .iter()
+        .any(|m| m.number == SET_COUNT && m.name == "SetCount"));
+}
+
+#[test]
+fn manifest_section_survives_compaction() {
+    let wasm = compact_wasm();
+    let section = custom_section(&wasm, "fvm_manifest")
+        .expect("fvm_manifest section missing from the compacted wasm");
+    let manifest = std::str::from_utf8(section).unwrap();
+
+    let version = format!("{{\"crate_version\":\"{}\",", env!("CARGO_PKG_VERSION"));
+    assert!(
+        manifest.starts_with(&version),
+        "unexpected manifest {}",
+        manifest
+    );
+    assert!(
+        manifest.contains("\"git_commit\":"),
+        "unexpected manifest {}",
+        manifest
+    );
+}